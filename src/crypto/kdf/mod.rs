@@ -1,6 +1,19 @@
 use ffi;
 // use libc::c_ulonglong;
+use crypto::pwhash;
+use crypto::secretbox;
+use crypto::shorthash;
 use randombytes::randombytes_into;
+use std::collections::HashMap;
+use utils;
+
+/// Re-exported so callers deriving a `MasterKey` from a passphrase don't need to reach into
+/// `pwhash` directly for a salt or one of the standard opslimit/memlimit presets.
+pub use crypto::pwhash::{
+    Salt, SALTBYTES, OpsLimit, MemLimit,
+    MEMLIMIT_INTERACTIVE, MEMLIMIT_MODERATE, MEMLIMIT_SENSITIVE,
+    OPSLIMIT_INTERACTIVE, OPSLIMIT_MODERATE, OPSLIMIT_SENSITIVE,
+};
 
 /// Minimum number of bytes in a derived key
 pub const DERIVED_KEY_BYTES_MIN: usize = ffi::crypto_kdf_blake2b_BYTES_MIN as usize;
@@ -26,6 +39,21 @@ pub fn generate_key() -> MasterKey {
     MasterKey(key)
 }
 
+impl MasterKey {
+    /// Derives a `MasterKey` from a passphrase via `pwhash` (Argon2id), filling exactly
+    /// `MASTER_KEY_BYTES`. Returns `None` if `libsodium` rejected `opslimit`/`memlimit`.
+    pub fn derive_from_passphrase(
+        passphrase: &[u8],
+        salt: &pwhash::Salt,
+        opslimit: pwhash::OpsLimit,
+        memlimit: pwhash::MemLimit,
+    ) -> Option<MasterKey> {
+        let mut key = [0; MASTER_KEY_BYTES];
+        pwhash::derive_key(&mut key, passphrase, salt, opslimit, memlimit).ok()?;
+        Some(MasterKey(key))
+    }
+}
+
 /// Generates a random `Context`.
 pub fn generate_context() -> Context {
     let mut context = [0; CONTEXT_BYTES];
@@ -47,11 +75,10 @@ pub struct Session {
 }
 
 impl Session {
-    /// Attempts to fill `buffer` with the next key in the sequence.
+    /// Derives the subkey at `subkey_id` into `buffer` without touching `self.index`.
     /// Returns `None` if `buffer` is shorter than `DERIVED_KEY_BYTES_MIN` or longer than
     /// `DERIVED_KEY_BYTES_MAX`, or if `libsodium` returned an error.
-    /// Otherwise returns `Some(i)` where `i` is the index used to fill `buffer`.
-    pub fn generate_next_key(&mut self, buffer: &mut [u8]) -> Option<u64> {
+    pub fn derive_at(&self, subkey_id: u64, buffer: &mut [u8]) -> Option<()> {
         let len = buffer.len();
         if len < DERIVED_KEY_BYTES_MIN || DERIVED_KEY_BYTES_MAX < len {
             return None;
@@ -60,15 +87,205 @@ impl Session {
             let subkey: *mut libc::c_uchar = std::mem::transmute(buffer.as_mut_ptr());
             let ctx: *const libc::c_char = std::mem::transmute(self.context.as_ref().as_ptr());
             let key: *const libc::c_uchar = std::mem::transmute(self.key.as_ref().as_ptr());
-            ffi::crypto_kdf_blake2b_derive_from_key(subkey, len, self.index, ctx, key)
+            ffi::crypto_kdf_blake2b_derive_from_key(subkey, len, subkey_id, ctx, key)
         };
-        self.index += 1;
         if i == 0 {
-            Some(self.index - 1)
+            Some(())
         } else {
             None
         }
     }
+
+    /// Attempts to fill `buffer` with the next key in the sequence.
+    /// Returns `None` if `buffer` is shorter than `DERIVED_KEY_BYTES_MIN` or longer than
+    /// `DERIVED_KEY_BYTES_MAX`, or if `libsodium` returned an error.
+    /// Otherwise returns `Some(i)` where `i` is the index used to fill `buffer`.
+    pub fn generate_next_key(&mut self, buffer: &mut [u8]) -> Option<u64> {
+        self.derive_at(self.index, buffer)?;
+        self.index += 1;
+        Some(self.index - 1)
+    }
+
+    /// Jumps this session's counter directly to `index` without deriving any of the skipped
+    /// subkeys.
+    pub fn skip_to(&mut self, index: u64) {
+        self.index = index;
+    }
+
+    /// Returns a lazy, non-mutating iterator of `DerivedKey<N>`s starting at the session's
+    /// current index. Backed by `derive_at`, so stepping it never advances `self.index`.
+    pub fn keys<const N: usize>(&self) -> Keys<'_, N> {
+        Keys {
+            session: self,
+            next_index: self.index,
+        }
+    }
+
+    /// Derives the next key in the sequence into a `DerivedKey<N>`. `N` is checked at compile
+    /// time, so unlike `generate_next_key` this cannot fail at runtime.
+    pub fn derive<const N: usize>(&mut self) -> DerivedKey<N> {
+        let () = AssertDerivedKeyLen::<N>::OK;
+        let mut buf = [0u8; N];
+        self.generate_next_key(&mut buf)
+            .expect("DerivedKey: N is statically within range");
+        DerivedKey(buf)
+    }
+
+    /// Serializes the fields needed to reconstruct this `Session` (key, context, index) into
+    /// a fixed-size byte vector, in the order expected by `from_bytes`.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SESSION_BYTES_LEN);
+        bytes.extend_from_slice(self.key.as_ref());
+        bytes.extend_from_slice(self.context.as_ref());
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes
+    }
+
+    /// Reconstructs a `Session` from the layout produced by `to_bytes`, going through
+    /// `SessionBuilder` so the same validation applies as any other construction path.
+    fn from_bytes(bytes: &[u8]) -> Option<Session> {
+        if bytes.len() != SESSION_BYTES_LEN {
+            return None;
+        }
+        let key = MasterKey::from_slice(&bytes[..MASTER_KEY_BYTES])?;
+        let context = Context::from_slice(
+            &bytes[MASTER_KEY_BYTES..MASTER_KEY_BYTES + CONTEXT_BYTES],
+        )?;
+        let mut index_bytes = [0u8; 8];
+        index_bytes.copy_from_slice(&bytes[MASTER_KEY_BYTES + CONTEXT_BYTES..]);
+        let index = u64::from_be_bytes(index_bytes);
+        SessionBuilder::new()
+            .index(index)
+            .context(context)
+            .key(key)
+            .build()
+    }
+
+    /// Seals this `Session`, plus an optional big-endian `expires` timestamp, into
+    /// `nonce || ciphertext` under `storage_key`. Reconstruct with `Session::open`.
+    pub fn seal(&self, storage_key: &secretbox::Key, expires: Option<u64>) -> Vec<u8> {
+        let nonce = secretbox::gen_nonce();
+        let mut plaintext = self.to_bytes();
+        if let Some(expires) = expires {
+            plaintext.extend_from_slice(&expires.to_be_bytes());
+        }
+        let ciphertext = secretbox::seal(&plaintext, &nonce, storage_key);
+        utils::memzero(&mut plaintext);
+        let mut sealed = Vec::with_capacity(secretbox::NONCEBYTES + ciphertext.len());
+        sealed.extend_from_slice(nonce.as_ref());
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Opens a blob produced by `Session::seal`, verifying its authentication tag and rejecting
+    /// it if `now` is past the expiry timestamp that was sealed alongside it.
+    pub fn open(
+        bytes: &[u8],
+        storage_key: &secretbox::Key,
+        now: u64,
+    ) -> Result<Session, SealError> {
+        if bytes.len() < secretbox::NONCEBYTES {
+            return Err(SealError::Malformed);
+        }
+        let (nonce_bytes, ciphertext) = bytes.split_at(secretbox::NONCEBYTES);
+        let nonce = secretbox::Nonce::from_slice(nonce_bytes).ok_or(SealError::Malformed)?;
+        let mut plaintext =
+            secretbox::open(ciphertext, &nonce, storage_key).map_err(|_| SealError::Crypto)?;
+
+        let result = (|| {
+            if plaintext.len() < SESSION_BYTES_LEN {
+                return Err(SealError::Malformed);
+            }
+            let (session_bytes, expiry_bytes) = plaintext.split_at(SESSION_BYTES_LEN);
+            if !expiry_bytes.is_empty() {
+                if expiry_bytes.len() != 8 {
+                    return Err(SealError::Malformed);
+                }
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(expiry_bytes);
+                if now > u64::from_be_bytes(buf) {
+                    return Err(SealError::Expired);
+                }
+            }
+            Session::from_bytes(session_bytes).ok_or(SealError::Malformed)
+        })();
+        utils::memzero(&mut plaintext);
+        result
+    }
+}
+
+/// Length in bytes of a `Session`'s serialized `key || context || index` layout.
+const SESSION_BYTES_LEN: usize = MASTER_KEY_BYTES + CONTEXT_BYTES + 8;
+
+/// Errors that can occur while opening a sealed `Session` blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealError {
+    /// The authentication tag did not verify, or `storage_key` was wrong.
+    Crypto,
+    /// The blob authenticated correctly, but its sealed expiry timestamp is before `now`.
+    Expired,
+    /// The blob was too short, or otherwise not shaped like a sealed `Session`.
+    Malformed,
+}
+
+/// A derived key of compile-time-known length `N`, produced by `Session::derive`. Has the same
+/// `AsRef`/`AsMut`/constant-time-`Eq`/zeroize-on-drop behavior as the crate's other key types.
+pub struct DerivedKey<const N: usize>([u8; N]);
+
+// Forces the `N >= DERIVED_KEY_BYTES_MIN && N <= DERIVED_KEY_BYTES_MAX` check to happen at
+// compile time: `Session::derive` evaluates `AssertDerivedKeyLen::<N>::OK`, which fails to
+// compile for an out-of-range `N` instead of returning `None` at runtime.
+struct AssertDerivedKeyLen<const N: usize>;
+
+impl<const N: usize> AssertDerivedKeyLen<N> {
+    const OK: () = assert!(
+        N >= DERIVED_KEY_BYTES_MIN && N <= DERIVED_KEY_BYTES_MAX,
+        "DerivedKey: N must be within DERIVED_KEY_BYTES_MIN..=DERIVED_KEY_BYTES_MAX"
+    );
+}
+
+impl<const N: usize> AsRef<[u8]> for DerivedKey<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<const N: usize> AsMut<[u8]> for DerivedKey<N> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl<const N: usize> PartialEq for DerivedKey<N> {
+    fn eq(&self, other: &Self) -> bool {
+        utils::memcmp(&self.0, &other.0)
+    }
+}
+
+impl<const N: usize> Eq for DerivedKey<N> {}
+
+impl<const N: usize> Drop for DerivedKey<N> {
+    fn drop(&mut self) {
+        utils::memzero(&mut self.0);
+    }
+}
+
+/// A lazy, non-mutating iterator over a `Session`'s key stream, returned by `Session::keys`.
+pub struct Keys<'a, const N: usize> {
+    session: &'a Session,
+    next_index: u64,
+}
+
+impl<'a, const N: usize> Iterator for Keys<'a, N> {
+    type Item = DerivedKey<N>;
+
+    fn next(&mut self) -> Option<DerivedKey<N>> {
+        let () = AssertDerivedKeyLen::<N>::OK;
+        let mut buf = [0u8; N];
+        self.session.derive_at(self.next_index, &mut buf)?;
+        self.next_index += 1;
+        Some(DerivedKey(buf))
+    }
 }
 
 /// A builder for `Session`s.
@@ -105,6 +322,19 @@ impl SessionBuilder {
         self.key(generate_key())
     }
 
+    /// Sets the key of a `SessionBuilder` by deriving it from a passphrase, overriding one if it
+    /// was already set. Returns `None` if `libsodium` rejected `opslimit`/`memlimit`.
+    pub fn passphrase_key(
+        &mut self,
+        passphrase: &[u8],
+        salt: &pwhash::Salt,
+        opslimit: pwhash::OpsLimit,
+        memlimit: pwhash::MemLimit,
+    ) -> Option<&mut Self> {
+        let key = MasterKey::derive_from_passphrase(passphrase, salt, opslimit, memlimit)?;
+        Some(self.key(key))
+    }
+
     /// Sets the context of a `SessionBuilder`, overriding one if it was already set.
     pub fn context(&mut self, context: Context) -> &mut Self {
         self.context = Some(context);
@@ -231,3 +461,406 @@ impl<'de> ::serde::Deserialize<'de> for Session {
         deserializer.deserialize_struct(SESSION_TYPE_STRING, SESSION_FIELDS_ARRAY, StructVisitor)
     }
 }
+
+/// Context used to derive each `Keyring`'s internal name-hashing subkey. Arbitrary but fixed, so
+/// every `Keyring` built from a given `MasterKey` maps names to the same contexts.
+const KEYRING_HASH_CONTEXT: Context = Context(*b"keyrhash");
+
+/// Manages many named key streams derived from a single `MasterKey`, with versioned rotation.
+/// Each name is hashed (keyed on a subkey of the `MasterKey`) into its own `Context`.
+pub struct Keyring {
+    master: MasterKey,
+    hash_key: shorthash::Key,
+    version: u64,
+    counters: HashMap<(String, u64), u64>,
+}
+
+impl Keyring {
+    /// Creates a new `Keyring` from a `MasterKey`, starting at version `0` with no contexts
+    /// derived yet.
+    pub fn new(master: MasterKey) -> Keyring {
+        let mut hash_session = SessionBuilder::new()
+            .key(master.clone())
+            .context(KEYRING_HASH_CONTEXT)
+            .index(0)
+            .build()
+            .expect("Keyring::new: a just-supplied key is always present");
+        let mut hash_key_bytes = [0u8; shorthash::KEYBYTES];
+        hash_session
+            .generate_next_key(&mut hash_key_bytes)
+            .expect("Keyring::new: shorthash::KEYBYTES is within DERIVED_KEY_BYTES_MIN..=MAX");
+        Keyring {
+            master,
+            hash_key: shorthash::Key(hash_key_bytes),
+            version: 0,
+            counters: HashMap::new(),
+        }
+    }
+
+    /// Deterministically hashes `name` and `version` into the `Context` used for that name's
+    /// key stream at that version.
+    fn context_for(&self, name: &str, version: u64) -> Context {
+        let mut input = Vec::with_capacity(name.len() + 8);
+        input.extend_from_slice(name.as_bytes());
+        input.extend_from_slice(&version.to_be_bytes());
+        let digest = shorthash::shorthash(&input, &self.hash_key);
+        Context::from_slice(digest.as_ref())
+            .expect("Keyring::context_for: shorthash output is always CONTEXT_BYTES long")
+    }
+
+    /// Derives the next key for `name` at the current version into `buffer`, advancing that
+    /// name's subkey counter. Returns `Some(i)` where `i` is the subkey id used, or `None` under
+    /// the same conditions as `Session::generate_next_key`.
+    pub fn derive(&mut self, name: &str, buffer: &mut [u8]) -> Option<u64> {
+        let context = self.context_for(name, self.version);
+        let mut session = SessionBuilder::new()
+            .key(self.master.clone())
+            .context(context)
+            .index(0)
+            .build()?;
+        let key = (name.to_owned(), self.version);
+        let counter = self.counters.get(&key).copied().unwrap_or(0);
+        session.skip_to(counter);
+        let used = session.generate_next_key(buffer)?;
+        self.counters.insert(key, counter + 1);
+        Some(used)
+    }
+
+    /// Derives the subkey at `subkey_id` for `name` at the current version into `buffer`,
+    /// without touching that name's subkey counter.
+    pub fn derive_named(&self, name: &str, subkey_id: u64, buffer: &mut [u8]) -> Option<()> {
+        self.derive_for_version(name, self.version, subkey_id, buffer)
+    }
+
+    /// Derives the subkey at `subkey_id` for `name` as it was at `version`, regardless of the
+    /// keyring's current version. Lets old streams still be read after `rotate()`.
+    pub fn derive_for_version(
+        &self,
+        name: &str,
+        version: u64,
+        subkey_id: u64,
+        buffer: &mut [u8],
+    ) -> Option<()> {
+        let context = self.context_for(name, version);
+        let session = SessionBuilder::new()
+            .key(self.master.clone())
+            .context(context)
+            .index(0)
+            .build()?;
+        session.derive_at(subkey_id, buffer)
+    }
+
+    /// Advances the keyring to a new version and returns it. Folded into every
+    /// subsequently-derived context.
+    pub fn rotate(&mut self) -> u64 {
+        self.version += 1;
+        self.version
+    }
+
+    /// The keyring's current version, as last advanced by `rotate()`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+const KEYRING_TYPE_STRING: &'static str = stringify!(Keyring);
+const KEYRING_MASTER_STRING: &'static str = "master";
+const KEYRING_HASH_KEY_STRING: &'static str = "hash_key";
+const KEYRING_VERSION_STRING: &'static str = "version";
+const KEYRING_COUNTERS_STRING: &'static str = "counters";
+const KEYRING_FIELDS_ARRAY: &'static [&'static str] = &[
+    KEYRING_MASTER_STRING,
+    KEYRING_HASH_KEY_STRING,
+    KEYRING_VERSION_STRING,
+    KEYRING_COUNTERS_STRING,
+];
+
+// ser and de implemented by hand, following the same pattern as `Session` above.
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Keyring {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let counters: Vec<(String, u64, u64)> = self
+            .counters
+            .iter()
+            .map(|(&(ref name, version), &counter)| (name.clone(), version, counter))
+            .collect();
+        let mut ser = serializer.serialize_struct(KEYRING_TYPE_STRING, 4)?;
+        ser.serialize_field(KEYRING_MASTER_STRING, &self.master)?;
+        ser.serialize_field(KEYRING_HASH_KEY_STRING, &self.hash_key)?;
+        ser.serialize_field(KEYRING_VERSION_STRING, &self.version)?;
+        ser.serialize_field(KEYRING_COUNTERS_STRING, &counters)?;
+        ser.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Keyring {
+    fn deserialize<D>(deserializer: D) -> Result<Keyring, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        struct StructVisitor;
+        impl<'de> ::serde::de::Visitor<'de> for StructVisitor {
+            type Value = Keyring;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                write!(formatter, "{}", KEYRING_TYPE_STRING)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: ::serde::de::MapAccess<'de>,
+            {
+                let mut master = None;
+                let mut hash_key = None;
+                let mut version = None;
+                let mut counters: Option<Vec<(String, u64, u64)>> = None;
+                for _ in 0..4 {
+                    let mapkey: &str = map.next_key()?.ok_or(
+                        ::serde::de::Error::invalid_length(KEYRING_FIELDS_ARRAY.len(), &self),
+                    )?;
+                    if mapkey == KEYRING_MASTER_STRING {
+                        master = Some(map.next_value()?);
+                    } else if mapkey == KEYRING_HASH_KEY_STRING {
+                        hash_key = Some(map.next_value()?);
+                    } else if mapkey == KEYRING_VERSION_STRING {
+                        version = Some(map.next_value()?);
+                    } else if mapkey == KEYRING_COUNTERS_STRING {
+                        counters = Some(map.next_value()?);
+                    } else {
+                        return Err(::serde::de::Error::unknown_field(
+                            mapkey,
+                            KEYRING_FIELDS_ARRAY,
+                        ));
+                    }
+                }
+                let master = master
+                    .ok_or_else(|| ::serde::de::Error::missing_field(KEYRING_MASTER_STRING))?;
+                let hash_key = hash_key
+                    .ok_or_else(|| ::serde::de::Error::missing_field(KEYRING_HASH_KEY_STRING))?;
+                let version = version
+                    .ok_or_else(|| ::serde::de::Error::missing_field(KEYRING_VERSION_STRING))?;
+                let counters: Vec<(String, u64, u64)> = counters
+                    .ok_or_else(|| ::serde::de::Error::missing_field(KEYRING_COUNTERS_STRING))?;
+                Ok(Keyring {
+                    master,
+                    hash_key,
+                    version,
+                    counters: counters
+                        .into_iter()
+                        .map(|(name, version, counter)| ((name, version), counter))
+                        .collect(),
+                })
+            }
+        }
+        deserializer.deserialize_struct(KEYRING_TYPE_STRING, KEYRING_FIELDS_ARRAY, StructVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_session() -> Session {
+        SessionBuilder::new()
+            .random_key()
+            .random_context()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        ::init().unwrap();
+        let session = test_session();
+        let storage_key = secretbox::gen_key();
+        let sealed = session.seal(&storage_key, None);
+        let opened = Session::open(&sealed, &storage_key, 0).unwrap();
+        assert!(opened == session);
+    }
+
+    #[test]
+    fn seal_open_rejects_tampering() {
+        ::init().unwrap();
+        let session = test_session();
+        let storage_key = secretbox::gen_key();
+        let mut sealed = session.seal(&storage_key, None);
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(Session::open(&sealed, &storage_key, 0) == Err(SealError::Crypto));
+    }
+
+    #[test]
+    fn seal_open_rejects_after_expiry() {
+        ::init().unwrap();
+        let session = test_session();
+        let storage_key = secretbox::gen_key();
+        let sealed = session.seal(&storage_key, Some(100));
+        assert!(Session::open(&sealed, &storage_key, 101) == Err(SealError::Expired));
+    }
+
+    #[test]
+    fn seal_open_accepts_at_expiry() {
+        ::init().unwrap();
+        let session = test_session();
+        let storage_key = secretbox::gen_key();
+        let sealed = session.seal(&storage_key, Some(100));
+        assert!(Session::open(&sealed, &storage_key, 100).is_ok());
+    }
+
+    #[test]
+    fn seal_open_never_expires_without_expires() {
+        ::init().unwrap();
+        let session = test_session();
+        let storage_key = secretbox::gen_key();
+        let sealed = session.seal(&storage_key, None);
+        assert!(Session::open(&sealed, &storage_key, u64::max_value()).is_ok());
+    }
+
+    #[test]
+    fn derive_matches_derive_at() {
+        ::init().unwrap();
+        let mut session = test_session();
+        let mut via_derive_at = [0u8; 32];
+        session.derive_at(0, &mut via_derive_at).unwrap();
+
+        let key: DerivedKey<32> = session.derive();
+        assert!(key.as_ref() == &via_derive_at[..]);
+    }
+
+    #[test]
+    fn keys_iterator_matches_derive_at() {
+        ::init().unwrap();
+        let session = test_session();
+        let mut expected = [0u8; 32];
+        session.derive_at(0, &mut expected).unwrap();
+
+        let first: DerivedKey<32> = session.keys().next().unwrap();
+        assert!(first.as_ref() == &expected[..]);
+    }
+
+    #[test]
+    fn skip_to_resumes_key_schedule() {
+        ::init().unwrap();
+        let mut session = test_session();
+        let mut expected = [0u8; 32];
+        session.derive_at(5, &mut expected).unwrap();
+
+        session.skip_to(5);
+        let mut got = [0u8; 32];
+        let used = session.generate_next_key(&mut got).unwrap();
+        assert_eq!(used, 5);
+        assert!(got == expected);
+    }
+
+    #[test]
+    fn keyring_derive_increments_counter() {
+        ::init().unwrap();
+        let mut keyring = Keyring::new(generate_key());
+        let mut buf = [0u8; 32];
+        let first = keyring.derive("room-a", &mut buf).unwrap();
+        let second = keyring.derive("room-a", &mut buf).unwrap();
+        assert_eq!(first, 0);
+        assert_eq!(second, 1);
+    }
+
+    #[test]
+    fn keyring_derive_namespaces_by_name() {
+        ::init().unwrap();
+        let mut keyring = Keyring::new(generate_key());
+        let mut room_a = [0u8; 32];
+        let mut room_b = [0u8; 32];
+        keyring.derive("room-a", &mut room_a).unwrap();
+        keyring.derive("room-b", &mut room_b).unwrap();
+        assert!(room_a != room_b);
+    }
+
+    #[test]
+    fn keyring_rotate_preserves_old_streams() {
+        ::init().unwrap();
+        let mut keyring = Keyring::new(generate_key());
+        let mut before = [0u8; 32];
+        keyring.derive("room-a", &mut before).unwrap();
+
+        keyring.rotate();
+        let mut after = [0u8; 32];
+        keyring.derive("room-a", &mut after).unwrap();
+        assert!(before != after);
+
+        let mut recovered = [0u8; 32];
+        keyring
+            .derive_for_version("room-a", 0, 0, &mut recovered)
+            .unwrap();
+        assert!(recovered == before);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn keyring_serde_round_trip() {
+        ::init().unwrap();
+        let mut keyring = Keyring::new(generate_key());
+        let mut buf = [0u8; 32];
+        keyring.derive("room-a", &mut buf).unwrap();
+        keyring.rotate();
+
+        let encoded = ::serde_json::to_vec(&keyring).unwrap();
+        let decoded: Keyring = ::serde_json::from_slice(&encoded).unwrap();
+        assert_eq!(keyring.version(), decoded.version());
+
+        let mut from_original = [0u8; 32];
+        let mut from_decoded = [0u8; 32];
+        keyring
+            .derive_for_version("room-a", 0, 0, &mut from_original)
+            .unwrap();
+        decoded
+            .derive_for_version("room-a", 0, 0, &mut from_decoded)
+            .unwrap();
+        assert!(from_original == from_decoded);
+    }
+
+    #[test]
+    fn derive_from_passphrase_is_deterministic() {
+        ::init().unwrap();
+        let salt = pwhash::gen_salt();
+        let a = MasterKey::derive_from_passphrase(
+            b"correct horse battery staple",
+            &salt,
+            pwhash::OPSLIMIT_INTERACTIVE,
+            pwhash::MEMLIMIT_INTERACTIVE,
+        )
+        .unwrap();
+        let b = MasterKey::derive_from_passphrase(
+            b"correct horse battery staple",
+            &salt,
+            pwhash::OPSLIMIT_INTERACTIVE,
+            pwhash::MEMLIMIT_INTERACTIVE,
+        )
+        .unwrap();
+        assert!(a == b);
+    }
+
+    #[test]
+    fn derive_from_passphrase_differs_per_passphrase() {
+        ::init().unwrap();
+        let salt = pwhash::gen_salt();
+        let a = MasterKey::derive_from_passphrase(
+            b"correct horse battery staple",
+            &salt,
+            pwhash::OPSLIMIT_INTERACTIVE,
+            pwhash::MEMLIMIT_INTERACTIVE,
+        )
+        .unwrap();
+        let b = MasterKey::derive_from_passphrase(
+            b"wrong horse battery staple",
+            &salt,
+            pwhash::OPSLIMIT_INTERACTIVE,
+            pwhash::MEMLIMIT_INTERACTIVE,
+        )
+        .unwrap();
+        assert!(a != b);
+    }
+}